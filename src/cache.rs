@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Default number of blocks to prefetch ahead of the last requested height.
+pub const DEFAULT_PREFETCH_WINDOW: u64 = 16;
+
+/// A bounded, height-keyed cache of raw blocks.
+///
+/// CLN walks `getrawblockbyheight` over strictly increasing heights during
+/// sync, and each miss costs two serial round-trips to florestad. This cache
+/// lets us serve a warm entry immediately while a background task keeps
+/// `window` blocks ahead of the last served height pre-fetched, turning the
+/// latency-bound serial loop into a pipelined one.
+pub struct BlockCache {
+    window: u64,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    entries: HashMap<u64, (String, String)>,
+    /// Heights a background prefetch has already been spawned for, but that
+    /// haven't landed in `entries` yet, so `mark_served` doesn't spawn
+    /// duplicate fetches for the same height on every overlapping call.
+    pending: HashSet<u64>,
+    last_served: u64,
+}
+
+impl BlockCache {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                pending: HashSet::new(),
+                last_served: 0,
+            }),
+        }
+    }
+
+    /// Returns the cached `(blockhash, hex_block)` for `height`, if present.
+    pub fn get(&self, height: u64) -> Option<(String, String)> {
+        self.inner.lock().unwrap().entries.get(&height).cloned()
+    }
+
+    /// Inserts a fetched block into the cache.
+    pub fn insert(&self, height: u64, blockhash: String, hex_block: String) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.insert(height, (blockhash, hex_block));
+        inner.pending.remove(&height);
+    }
+
+    /// Clears the pending marker for `height`, allowing a later call to
+    /// retry prefetching it. Call this when a background fetch for `height`
+    /// failed, so it isn't stuck "pending" forever.
+    pub fn clear_pending(&self, height: u64) {
+        self.inner.lock().unwrap().pending.remove(&height);
+    }
+
+    /// Marks `height` as served, advancing `last_served` (which only ever
+    /// moves forward, so a stale/out-of-order call can't regress it) and
+    /// evicting every cached entry and pending marker below it. Returns the
+    /// heights in `last_served+1 ..= last_served+window` that should be
+    /// prefetched next (i.e. neither cached nor already being fetched by an
+    /// earlier call).
+    pub fn mark_served(&self, height: u64) -> Vec<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_served = inner.last_served.max(height);
+        let last_served = inner.last_served;
+        inner.entries.retain(|&h, _| h >= last_served);
+        inner.pending.retain(|&h| h >= last_served);
+
+        let to_fetch: Vec<u64> = (last_served + 1..=last_served + self.window)
+            .filter(|h| !inner.entries.contains_key(h) && !inner.pending.contains(h))
+            .collect();
+        inner.pending.extend(&to_fetch);
+
+        to_fetch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mark_served_prefetches_the_window_ahead() {
+        let cache = BlockCache::new(4);
+        assert_eq!(cache.mark_served(100), vec![101, 102, 103, 104]);
+    }
+
+    #[test]
+    fn mark_served_skips_cached_and_pending_heights() {
+        let cache = BlockCache::new(4);
+        cache.insert(102, "hash102".to_string(), "block102".to_string());
+        assert_eq!(cache.mark_served(100), vec![101, 103, 104]);
+        assert_eq!(cache.mark_served(100), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn mark_served_evicts_entries_below_the_served_height() {
+        let cache = BlockCache::new(4);
+        cache.insert(99, "hash99".to_string(), "block99".to_string());
+        cache.insert(100, "hash100".to_string(), "block100".to_string());
+
+        cache.mark_served(100);
+
+        assert_eq!(cache.get(99), None);
+        assert_eq!(
+            cache.get(100),
+            Some(("hash100".to_string(), "block100".to_string()))
+        );
+    }
+
+    #[test]
+    fn mark_served_is_monotonic_and_cannot_regress() {
+        let cache = BlockCache::new(4);
+        cache.insert(150, "hash150".to_string(), "block150".to_string());
+
+        assert_eq!(cache.mark_served(200), vec![201, 202, 203, 204]);
+
+        // last_served stays at 200: nothing around 150 is handed out again,
+        // and 201..=204 are still pending from the call above.
+        assert_eq!(cache.mark_served(150), Vec::<u64>::new());
+        assert_eq!(cache.get(150), None);
+    }
+
+    #[test]
+    fn clear_pending_allows_a_height_to_be_prefetched_again() {
+        let cache = BlockCache::new(2);
+        assert_eq!(cache.mark_served(100), vec![101, 102]);
+        assert_eq!(cache.mark_served(100), Vec::<u64>::new());
+
+        cache.clear_pending(101);
+
+        assert_eq!(cache.mark_served(100), vec![101]);
+    }
+}
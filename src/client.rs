@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::Client;
+
+/// A JSON-RPC client for talking to florestad.
+///
+/// This mirrors the shape of LDK's `BitcoindClient`/`RpcClient`: it owns the
+/// connection details (host, port, optional credentials) and a monotonic
+/// request id counter, so callers just hand it a method name and params
+/// instead of reaching into global state.
+pub struct FlorestadClient {
+    http: Client,
+    host: String,
+    port: u16,
+    auth: Option<String>,
+    next_id: AtomicU32,
+}
+
+impl FlorestadClient {
+    pub fn new(
+        host: String,
+        port: u16,
+        user: Option<String>,
+        password: Option<String>,
+        cookie_path: Option<String>,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let auth = Self::build_auth(user, password, cookie_path)?;
+        let http = Client::builder().timeout(timeout).build()?;
+
+        Ok(Self {
+            http,
+            host,
+            port,
+            auth,
+            next_id: AtomicU32::new(0),
+        })
+    }
+
+    /// Builds the value of the `Authorization: Basic <...>` header, either
+    /// from a `user:password` pair or from a cookie file of the same shape
+    /// florestad/bitcoind write to disk.
+    fn build_auth(
+        user: Option<String>,
+        password: Option<String>,
+        cookie_path: Option<String>,
+    ) -> anyhow::Result<Option<String>> {
+        if let Some(cookie_path) = cookie_path {
+            let cookie = std::fs::read_to_string(&cookie_path).map_err(|e| {
+                anyhow::anyhow!("failed to read rpc cookie file {cookie_path}: {e}")
+            })?;
+            return Ok(Some(BASE64.encode(cookie.trim())));
+        }
+
+        if let Some(user) = user {
+            let password = password.unwrap_or_default();
+            return Ok(Some(BASE64.encode(format!("{user}:{password}"))));
+        }
+
+        Ok(None)
+    }
+
+    fn endpoint(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+
+    /// Performs a json-rpc request to florestad
+    pub async fn call(&self, method: &str, params: String) -> anyhow::Result<String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let request = format!(
+            "{{\"jsonrpc\":\"2.0\", \"id\":{id}, \"method\":\"{method}\", \"params\": [{params}]}}",
+        );
+
+        let mut req = self
+            .http
+            .post(self.endpoint())
+            .body(request)
+            .header("Content-Type", "application/json");
+
+        if let Some(auth) = &self.auth {
+            req = req.header("Authorization", format!("Basic {auth}"));
+        }
+
+        let res = req.send().await?.text().await?;
+
+        Ok(res)
+    }
+}
@@ -1,9 +1,32 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::{Error, Ok};
+use cln_plugin::options::{ConfigOption, Value as OValue};
 use cln_plugin::{Builder, Plugin};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+mod cache;
+mod client;
+mod res;
+
+use cache::{BlockCache, DEFAULT_PREFETCH_WINDOW};
+use client::FlorestadClient;
+use res::{VerboseBlock, VerboseBlockHeader, VerboseTransaction};
+
+const DEFAULT_RPC_HOST: &str = "127.0.0.1";
+const DEFAULT_RPC_PORT: i64 = 8080;
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Shared state handed to every RPC method: the florestad connection and the
+/// block prefetch cache, both cheaply `Clone`-able via `Arc`.
+#[derive(Clone)]
+struct PluginState {
+    client: Arc<FlorestadClient>,
+    cache: Arc<BlockCache>,
+}
+
 const GET_CHAIN_INFO_HELP: &str = r"
     Returns general information about the chain we are in.
 
@@ -51,13 +74,83 @@ const GET_RAW_BLOCK_BY_HEIGHT_HELP: &str = r"
         block: hex-encoded block (string)
 ";
 
-type FlorestaPlugin = Plugin<Client>;
+const GET_RAW_TRANSACTION_HELP: &str = r"
+    Returns the raw transaction for `txid`, Core-style.
+
+    Arguments:
+        txid: the transaction id to look up (string)
+        verbose: whether to return the decoded transaction instead of just its hex (bool, default false)
+
+    Returns:
+        hex: the hex-encoded transaction (string)
+        When verbose is true, also returns Core's usual fields: txid, hash, version,
+        size, vsize, weight, locktime, vin, vout, blockhash, confirmations, time, blocktime
+";
+
+const GET_BLOCK_HELP: &str = r"
+    Returns the decoded block for `blockhash`, Core-style verbose mode.
+
+    Arguments:
+        blockhash: the block hash to look up (string)
+
+    Returns:
+        hash, confirmations, height, version, merkleroot, time, nonce, bits,
+        previousblockhash, nextblockhash and the list of txids in `tx`
+";
+
+const GET_BLOCK_HEADER_HELP: &str = r"
+    Returns the decoded block header for `blockhash`, Core-style verbose mode.
+
+    Arguments:
+        blockhash: the block hash to look up (string)
+
+    Returns:
+        hash, confirmations, height, version, merkleroot, time, nonce, bits,
+        previousblockhash and nextblockhash
+";
+
+type FlorestaPlugin = Plugin<PluginState>;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let client = Client::new();
-
-    if let Some(plugin) = Builder::new(tokio::io::stdin(), tokio::io::stdout())
+    let rpc_host_opt = ConfigOption::new(
+        "floresta-rpc-host",
+        OValue::String(DEFAULT_RPC_HOST.to_string()),
+        "Host where florestad's JSON-RPC server is listening",
+    );
+    let rpc_port_opt = ConfigOption::new(
+        "floresta-rpc-port",
+        OValue::Integer(DEFAULT_RPC_PORT),
+        "Port where florestad's JSON-RPC server is listening",
+    );
+    let rpc_user_opt = ConfigOption::new(
+        "floresta-rpc-user",
+        OValue::OptString,
+        "Username for florestad's JSON-RPC basic auth",
+    );
+    let rpc_password_opt = ConfigOption::new(
+        "floresta-rpc-password",
+        OValue::OptString,
+        "Password for florestad's JSON-RPC basic auth",
+    );
+    let rpc_cookie_opt = ConfigOption::new(
+        "floresta-rpc-cookie",
+        OValue::OptString,
+        "Path to florestad's .cookie file, used for auth instead of user/password",
+    );
+    let prefetch_window_opt = ConfigOption::new(
+        "floresta-prefetch-window",
+        OValue::Integer(DEFAULT_PREFETCH_WINDOW as i64),
+        "How many blocks ahead of the last requested height to prefetch",
+    );
+
+    let Some(plugin) = Builder::new(tokio::io::stdin(), tokio::io::stdout())
+        .option(rpc_host_opt.clone())
+        .option(rpc_port_opt.clone())
+        .option(rpc_user_opt.clone())
+        .option(rpc_password_opt.clone())
+        .option(rpc_cookie_opt.clone())
+        .option(prefetch_window_opt.clone())
         .rpcmethod("getchaininfo", GET_CHAIN_INFO_HELP, get_blockchain_info)
         .rpcmethod(
             "sendrawtransaction",
@@ -71,9 +164,58 @@ async fn main() -> Result<(), Error> {
             GET_RAW_BLOCK_BY_HEIGHT_HELP,
             get_raw_block_by_height,
         )
-        .start(client)
+        .rpcmethod(
+            "getrawtransaction",
+            GET_RAW_TRANSACTION_HELP,
+            get_raw_transaction,
+        )
+        .rpcmethod("getblock", GET_BLOCK_HELP, get_block)
+        .rpcmethod("getblockheader", GET_BLOCK_HEADER_HELP, get_block_header)
+        .configure()
         .await?
-    {
+    else {
+        return Ok(());
+    };
+
+    let host = match plugin.option(&rpc_host_opt)? {
+        OValue::String(host) => host,
+        _ => DEFAULT_RPC_HOST.to_string(),
+    };
+    let port = match plugin.option(&rpc_port_opt)? {
+        OValue::Integer(port) => u16::try_from(port).map_err(|_| {
+            anyhow::anyhow!("floresta-rpc-port must be between 0 and 65535, got {port}")
+        })?,
+        _ => DEFAULT_RPC_PORT as u16,
+    };
+    let user = match plugin.option(&rpc_user_opt)? {
+        OValue::String(user) => Some(user),
+        _ => None,
+    };
+    let password = match plugin.option(&rpc_password_opt)? {
+        OValue::String(password) => Some(password),
+        _ => None,
+    };
+    let cookie = match plugin.option(&rpc_cookie_opt)? {
+        OValue::String(cookie) => Some(cookie),
+        _ => None,
+    };
+    let prefetch_window = match plugin.option(&prefetch_window_opt)? {
+        OValue::Integer(window) if window > 0 => window as u64,
+        OValue::Integer(window) => {
+            return Err(anyhow::anyhow!(
+                "floresta-prefetch-window must be positive, got {window}"
+            ))
+        }
+        _ => DEFAULT_PREFETCH_WINDOW,
+    };
+
+    let client = FlorestadClient::new(host, port, user, password, cookie, RPC_TIMEOUT)?;
+    let state = PluginState {
+        client: Arc::new(client),
+        cache: Arc::new(BlockCache::new(prefetch_window)),
+    };
+
+    if let Some(plugin) = plugin.start(state).await? {
         let _ = plugin.join().await;
     }
     Ok(())
@@ -89,8 +231,8 @@ async fn getutxout(p: FlorestaPlugin, v: serde_json::Value) -> Result<serde_json
         Some((txid, vout)) => (txid, vout),
         _ => return Err(Error::msg("bad request".to_owned())),
     };
-    
-    let res = rpc_call(&state, "gettxout", format!("{txid}, {vout}")).await?;
+
+    let res = rpc_call(&state.client, "gettxout", format!("{txid}, {vout}")).await?;
     let res = serde_json::from_str::<JsonRpcResult<GetUtxoResult>>(&res)?;
 
     match res.result {
@@ -114,7 +256,7 @@ async fn send_raw_transaction(
     let Some(tx) = v.get("tx") else {
         return Err(Error::msg("bad request".to_owned()));
     };
-    let res = rpc_call(&state, "sendrawtransaction", tx.to_string()).await?;
+    let res = rpc_call(&state.client, "sendrawtransaction", tx.to_string()).await?;
     let res: JsonRpcResult<String> = serde_json::from_str(&res)?;
 
     match res.error {
@@ -123,19 +265,86 @@ async fn send_raw_transaction(
     }
 }
 
+/// Confirmation targets (in blocks) we report feerates for, roughly matching
+/// CLN's notion of high-priority, normal and background priority.
+const FEE_TARGETS: [u32; 4] = [2, 6, 12, 100];
+
+/// Feerate floor used when florestad can't tell us its mempool minimum.
+const DEFAULT_MIN_RELAY_FEERATE_SAT_VB: f64 = 1.0;
+
+/// CLN's own minimum accepted feerate. Reporting anything below this would
+/// just get clamped/rejected by lightningd, so `feerate_floor` is always
+/// raised to at least this value.
+const CLN_MIN_FEERATE_SAT_KWU: u64 = 253;
+
+/// Converts a feerate given in sat/vB into sat/kWU, the unit CLN expects.
+///
+/// 1 vB = 4 weight units, so `sat_per_kwu = sat_per_vb * 1000 / 4 = sat_per_vb * 250`.
+fn sat_per_vb_to_sat_per_kwu(sat_per_vb: f64) -> u64 {
+    (sat_per_vb * 250.0).round() as u64
+}
+
+/// Converts a feerate given in BTC/kvB (the unit Core-style RPCs use) into sat/vB.
+fn btc_per_kvb_to_sat_per_vb(btc_per_kvb: f64) -> f64 {
+    // 100_000_000 sat/BTC / 1000 vB/kvB, folded into one factor so exact
+    // inputs like 0.00001 BTC/kvB come out as exactly 1.0 sat/vB.
+    btc_per_kvb * 100_000.0
+}
+
+/// Core's `estimatesmartfee` RPC, which florestad mirrors. Core dropped the
+/// older `estimatefee` RPC in 0.17, so this is the name to use.
+const ESTIMATE_SMART_FEE_METHOD: &str = "estimatesmartfee";
+
+/// Asks florestad for an estimate, in sat/vB, to confirm within `target` blocks.
+/// Returns `None` if florestad has no usable estimate for that target (no
+/// estimate, a transport/parse error, or a non-positive feerate).
+async fn estimate_smart_fee(client: &FlorestadClient, target: u32) -> Option<f64> {
+    let res = rpc_call(client, ESTIMATE_SMART_FEE_METHOD, format!("{target}"))
+        .await
+        .ok()?;
+    let res = serde_json::from_str::<JsonRpcResult<EstimateSmartFeeResult>>(&res).ok()?;
+    let feerate = res.result.and_then(|r| r.feerate)?;
+    (feerate > 0.0).then(|| btc_per_kvb_to_sat_per_vb(feerate))
+}
+
+/// Asks florestad for its mempool minimum relay feerate, in sat/vB, falling
+/// back to `DEFAULT_MIN_RELAY_FEERATE_SAT_VB` on any transport/parse error
+/// so a florestad hiccup degrades gracefully instead of failing the whole call.
+async fn min_relay_feerate(client: &FlorestadClient) -> f64 {
+    let Ok(res) = rpc_call(client, "getmempoolinfo", "".into()).await else {
+        return DEFAULT_MIN_RELAY_FEERATE_SAT_VB;
+    };
+
+    serde_json::from_str::<JsonRpcResult<GetMempoolInfo>>(&res)
+        .ok()
+        .and_then(|r| r.result)
+        .map(|info| btc_per_kvb_to_sat_per_vb(info.min_relay_tx_fee))
+        .unwrap_or(DEFAULT_MIN_RELAY_FEERATE_SAT_VB)
+}
+
 /// Estimates the fee needed for inclusing in `n` blocks
 async fn estimate_fees(
     p: FlorestaPlugin,
     _v: serde_json::Value,
 ) -> Result<serde_json::Value, Error> {
+    let state = p.state();
+
+    let min_relay_sat_vb = min_relay_feerate(&state.client).await;
+    let feerate_floor = sat_per_vb_to_sat_per_kwu(min_relay_sat_vb).max(CLN_MIN_FEERATE_SAT_KWU);
+
+    let mut feerates = Vec::with_capacity(FEE_TARGETS.len());
+    for target in FEE_TARGETS {
+        let feerate = match estimate_smart_fee(&state.client, target).await {
+            Some(sat_vb) => sat_per_vb_to_sat_per_kwu(sat_vb).max(feerate_floor),
+            None => feerate_floor,
+        };
+        feerates.push(json!({ "blocks": target, "feerate": feerate }));
+    }
+
     Ok(json!({
-    "feerate_floor": 1_000,
-    "feerates": [
-        { "blocks": 2, "feerate": 1_000 },
-        { "blocks": 6, "feerate": 1_000 },
-        { "blocks": 12, "feerate": 1_000 },
-        { "blocks": 100, "feerate": 1_000 }
-    ]}))
+        "feerate_floor": feerate_floor,
+        "feerates": feerates,
+    }))
 }
 
 /// Returns general info about our chain
@@ -143,8 +352,8 @@ async fn get_blockchain_info(
     p: FlorestaPlugin,
     _v: serde_json::Value,
 ) -> Result<serde_json::Value, Error> {
-    let client = p.state();
-    let chaininfo = rpc_call(&client, "getblockchaininfo", "".into()).await?;
+    let state = p.state();
+    let chaininfo = rpc_call(&state.client, "getblockchaininfo", "".into()).await?;
     let chaininfo = serde_json::from_str::<JsonRpcResult<GetBlockchainInfo>>(&chaininfo)?
         .result
         .unwrap();
@@ -157,73 +366,189 @@ async fn get_blockchain_info(
     }))
 }
 
+/// Fetches the block at `height` from florestad as `(blockhash, hex-encoded block)`.
+/// Returns `None` if florestad doesn't know about this height (yet).
+async fn fetch_block(client: &FlorestadClient, height: u64) -> Option<(String, String)> {
+    let block_hash = rpc_call(client, "getblockhash", format!("{height}"))
+        .await
+        .ok()?;
+    let block_hash = serde_json::from_str::<JsonRpcResult<String>>(&block_hash)
+        .ok()?
+        .result?;
+
+    let verbosity = 0;
+    let block = rpc_call(client, "getblock", format!("\"{block_hash}\", {verbosity}"))
+        .await
+        .ok()?;
+    let block = serde_json::from_str::<JsonRpcResult<Vec<u8>>>(&block)
+        .ok()?
+        .result?;
+
+    Some((block_hash, hex::encode(block)))
+}
+
 /// Returns a hex-encoded block given a height
+///
+/// CLN requests these in strictly increasing order during sync, so besides
+/// answering from `state.cache` when possible, this also kicks off background
+/// fetches for the next `floresta-prefetch-window` heights so later calls can
+/// be served from a warm cache instead of paying two serial round-trips each.
 async fn get_raw_block_by_height(
     p: FlorestaPlugin,
     v: serde_json::Value,
 ) -> Result<serde_json::Value, Error> {
     let state = p.state();
-    let height = v["height"]
-        .as_u64()
-        .expect("lightningd sent an invalid request");
-
-    let verbosity = 0;
+    let Some(height) = v.get("height").and_then(|height| height.as_u64()) else {
+        return Err(Error::msg("bad request".to_owned()));
+    };
 
-    let block_hash = rpc_call(&state, "getblockhash", format!("{}", height)).await?;
-    let block_hash = serde_json::from_str::<JsonRpcResult<String>>(&block_hash)?;
+    let entry = match state.cache.get(height) {
+        Some(entry) => Some(entry),
+        None => fetch_block(&state.client, height).await,
+    };
 
-    if block_hash.result.is_none() {
+    let Some((blockhash, block)) = entry else {
         return Ok(json!({
             "blockhash": null,
             "block": null,
         }));
+    };
+
+    state.cache.insert(height, blockhash.clone(), block.clone());
+
+    for prefetch_height in state.cache.mark_served(height) {
+        let client = state.client.clone();
+        let cache = state.cache.clone();
+        tokio::spawn(async move {
+            match fetch_block(&client, prefetch_height).await {
+                Some((blockhash, block)) => cache.insert(prefetch_height, blockhash, block),
+                None => cache.clear_pending(prefetch_height),
+            }
+        });
     }
 
-    let block = rpc_call(
-        &state,
-        "getblock",
-        format!("\"{}\", {}", block_hash.result.as_ref().unwrap(), verbosity),
+    Ok(json!({
+        "blockhash": blockhash,
+        "block": block,
+    }))
+}
+
+/// Returns a transaction, Core-style, optionally decoded
+async fn get_raw_transaction(
+    p: FlorestaPlugin,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let state = p.state();
+    let Some(txid) = v.get("txid").and_then(|txid| txid.as_str()) else {
+        return Err(Error::msg("bad request".to_owned()));
+    };
+    let verbose = v
+        .get("verbose")
+        .and_then(|verbose| verbose.as_bool())
+        .unwrap_or(false);
+
+    let res = rpc_call(
+        &state.client,
+        "getrawtransaction",
+        format!("\"{txid}\", {verbose}"),
     )
     .await?;
 
-    let block = serde_json::from_str::<JsonRpcResult<Vec<u8>>>(&block)?;
-    if let Some(block) = block.result {
-        let block_data = hex::encode(&block);
-        return Ok(json!({
-            "blockhash": block_hash.result,
-            "block": block_data,
-        }));
+    if !verbose {
+        let res = serde_json::from_str::<JsonRpcResult<String>>(&res)?;
+        return Ok(json!({ "hex": res.result }));
     }
 
+    let res = serde_json::from_str::<JsonRpcResult<VerboseTransaction>>(&res)?
+        .result
+        .ok_or_else(|| Error::msg("transaction not found".to_owned()))?;
+
     Ok(json!({
-        "blockhash": null,
-        "block": null,
+        "txid": res.txid,
+        "hash": res.hash,
+        "version": res.version,
+        "size": res.size,
+        "vsize": res.vsize,
+        "weight": res.weight,
+        "locktime": res.locktime,
+        "vin": res.vin,
+        "vout": res.vout,
+        "hex": res.hex,
+        "blockhash": res.blockhash,
+        "confirmations": res.confirmations,
+        "time": res.time,
+        "blocktime": res.blocktime,
     }))
 }
 
-// TODO: Move this to the plugin context
-static mut IDS: u32 = 0;
+/// Returns a block, Core-style verbose mode
+async fn get_block(p: FlorestaPlugin, v: serde_json::Value) -> Result<serde_json::Value, Error> {
+    let state = p.state();
+    let Some(blockhash) = v.get("blockhash").and_then(|blockhash| blockhash.as_str()) else {
+        return Err(Error::msg("bad request".to_owned()));
+    };
 
-/// Performs a json-rpc request to florestad
-async fn rpc_call(client: &Client, method: &str, params: String) -> anyhow::Result<String> {
-    let request = unsafe {
-        format!(
-        "{{\"jsonrpc\":\"2.0\", \"id\":{IDS}, \"method\":\"{method}\", \"params\": [{params}]}}",
-    )
+    let res = rpc_call(&state.client, "getblock", format!("\"{blockhash}\", 1")).await?;
+    let res = serde_json::from_str::<JsonRpcResult<VerboseBlock>>(&res)?
+        .result
+        .ok_or_else(|| Error::msg("block not found".to_owned()))?;
+
+    Ok(json!({
+        "hash": res.hash,
+        "confirmations": res.confirmations,
+        "height": res.height,
+        "version": res.version,
+        "merkleroot": res.merkleroot,
+        "time": res.time,
+        "nonce": res.nonce,
+        "bits": res.bits,
+        "previousblockhash": res.previousblockhash,
+        "nextblockhash": res.nextblockhash,
+        "tx": res.tx,
+    }))
+}
+
+/// Returns a block header, Core-style verbose mode
+async fn get_block_header(
+    p: FlorestaPlugin,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let state = p.state();
+    let Some(blockhash) = v.get("blockhash").and_then(|blockhash| blockhash.as_str()) else {
+        return Err(Error::msg("bad request".to_owned()));
     };
 
-    unsafe { IDS += 1 };
+    let res = rpc_call(
+        &state.client,
+        "getblockheader",
+        format!("\"{blockhash}\", true"),
+    )
+    .await?;
+    let res = serde_json::from_str::<JsonRpcResult<VerboseBlockHeader>>(&res)?
+        .result
+        .ok_or_else(|| Error::msg("block not found".to_owned()))?;
 
-    let res = client
-        .post("http://127.0.0.1:8080")
-        .body(request)
-        .header("Content-Type", "application/json")
-        .send()
-        .await?
-        .text()
-        .await?;
+    Ok(json!({
+        "hash": res.hash,
+        "confirmations": res.confirmations,
+        "height": res.height,
+        "version": res.version,
+        "merkleroot": res.merkleroot,
+        "time": res.time,
+        "nonce": res.nonce,
+        "bits": res.bits,
+        "previousblockhash": res.previousblockhash,
+        "nextblockhash": res.nextblockhash,
+    }))
+}
 
-    anyhow::Ok(res)
+/// Performs a json-rpc request to florestad
+async fn rpc_call(
+    client: &FlorestadClient,
+    method: &str,
+    params: String,
+) -> anyhow::Result<String> {
+    client.call(method, params).await
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -245,6 +570,18 @@ struct TxOut {
     script_pubkey: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct GetMempoolInfo {
+    #[serde(rename = "minrelaytxfee")]
+    min_relay_tx_fee: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EstimateSmartFeeResult {
+    feerate: Option<f64>,
+    errors: Option<Vec<String>>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct GetBlockchainInfo {
     best_block: String,
@@ -260,3 +597,28 @@ struct GetBlockchainInfo {
     root_hashes: Vec<String>,
     validated: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sat_per_vb_to_sat_per_kwu_converts_using_the_250x_factor() {
+        assert_eq!(sat_per_vb_to_sat_per_kwu(1.0), 250);
+        assert_eq!(sat_per_vb_to_sat_per_kwu(2.0), 500);
+        assert_eq!(sat_per_vb_to_sat_per_kwu(0.0), 0);
+    }
+
+    #[test]
+    fn sat_per_vb_to_sat_per_kwu_rounds_to_the_nearest_integer() {
+        assert_eq!(sat_per_vb_to_sat_per_kwu(1.001), 250);
+        assert_eq!(sat_per_vb_to_sat_per_kwu(1.002), 251);
+    }
+
+    #[test]
+    fn btc_per_kvb_to_sat_per_vb_converts_units() {
+        // 0.00001 BTC/kvB == 1000 sat/kvB == 1 sat/vB
+        assert_eq!(btc_per_kvb_to_sat_per_vb(0.00001), 1.0);
+        assert_eq!(btc_per_kvb_to_sat_per_vb(0.00002), 2.0);
+    }
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+/// Verbose `getrawtransaction` result, mirroring Bitcoin Core's decoded
+/// transaction JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerboseTransaction {
+    pub txid: String,
+    pub hash: String,
+    pub version: i32,
+    pub size: u32,
+    pub vsize: u32,
+    pub weight: u32,
+    pub locktime: u32,
+    pub vin: Vec<Vin>,
+    pub vout: Vec<Vout>,
+    pub hex: String,
+    pub blockhash: Option<String>,
+    pub confirmations: Option<u32>,
+    pub blocktime: Option<u32>,
+    pub time: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vin {
+    pub txid: Option<String>,
+    pub vout: Option<u32>,
+    pub script_sig: Option<ScriptSig>,
+    /// Present instead of `txid`/`vout`/`script_sig` for a coinbase input:
+    /// the hex-encoded coinbase script.
+    pub coinbase: Option<String>,
+    pub sequence: u32,
+    pub txinwitness: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScriptSig {
+    pub asm: String,
+    pub hex: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vout {
+    pub value: f64,
+    pub n: u32,
+    pub script_pub_key: ScriptPubKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScriptPubKey {
+    pub asm: String,
+    pub hex: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub address: Option<String>,
+}
+
+/// Verbose `getblock` result, mirroring Bitcoin Core's decoded block JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerboseBlock {
+    pub hash: String,
+    pub confirmations: i64,
+    pub height: u32,
+    pub version: i32,
+    pub merkleroot: String,
+    pub time: u32,
+    pub nonce: u32,
+    pub bits: String,
+    pub previousblockhash: Option<String>,
+    pub nextblockhash: Option<String>,
+    pub tx: Vec<String>,
+}
+
+/// Verbose `getblockheader` result, mirroring Bitcoin Core's decoded block
+/// header JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerboseBlockHeader {
+    pub hash: String,
+    pub confirmations: i64,
+    pub height: u32,
+    pub version: i32,
+    pub merkleroot: String,
+    pub time: u32,
+    pub nonce: u32,
+    pub bits: String,
+    pub previousblockhash: Option<String>,
+    pub nextblockhash: Option<String>,
+}